@@ -0,0 +1,202 @@
+//! A `--bench <workload.json>` mode that replays a declarative workload
+//! against the patchstorage API and reports throughput, so maintainers can
+//! track download performance regressions over time.
+
+use crate::{
+    download_patch_bytes, get_patch_metadata, retry_middleware, GetPatchesRequest, PagedPatches,
+    Patch, Platform,
+};
+use anyhow::{Context, Result};
+use camino::Utf8PathBuf;
+use http::Extensions;
+use reqwest::Client;
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware, Middleware, Next};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{Mutex, Semaphore};
+use tokio::task::JoinSet;
+
+fn default_jobs() -> usize {
+    4
+}
+
+/// A declarative benchmark workload: which platforms and pages to sweep,
+/// how much concurrency to use, and where to optionally publish results.
+#[derive(Debug, Deserialize)]
+struct Workload {
+    platforms: Vec<Platform>,
+    start_page: usize,
+    end_page: usize,
+    #[serde(default = "default_jobs")]
+    jobs: usize,
+    results_url: Option<String>,
+}
+
+/// Latency percentiles (in milliseconds) over one benchmark run's
+/// per-patch metadata+download requests.
+#[derive(Debug, Serialize)]
+struct LatencyPercentiles {
+    p50_ms: f64,
+    p90_ms: f64,
+    p99_ms: f64,
+}
+
+/// The structured report emitted to stdout, and optionally POSTed to
+/// `workload.results_url`.
+#[derive(Debug, Serialize)]
+struct Report {
+    patches_processed: u64,
+    bytes_downloaded: u64,
+    wall_clock_ms: u128,
+    retries_triggered: u64,
+    latency: LatencyPercentiles,
+}
+
+/// Counts every request that actually reaches the transport, including
+/// retries, by sitting inside `RetryTransientMiddleware`'s retry loop.
+struct CountingMiddleware {
+    attempts: Arc<AtomicU64>,
+}
+
+#[async_trait::async_trait]
+impl Middleware for CountingMiddleware {
+    async fn handle(
+        &self,
+        req: reqwest::Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<reqwest::Response> {
+        self.attempts.fetch_add(1, Ordering::Relaxed);
+        next.run(req, extensions).await
+    }
+}
+
+/// Runs the workload described by `workload_path` and prints a [`Report`].
+pub(crate) async fn run(workload_path: &Utf8PathBuf) -> Result<()> {
+    let raw = std::fs::read_to_string(workload_path)
+        .with_context(|| format!("reading workload file `{workload_path}`"))?;
+    let workload: Workload = serde_json::from_str(&raw)
+        .with_context(|| format!("parsing workload file `{workload_path}`"))?;
+
+    let attempts = Arc::new(AtomicU64::new(0));
+    let client = ClientBuilder::new(Client::new())
+        .with(retry_middleware())
+        .with(CountingMiddleware {
+            attempts: Arc::clone(&attempts),
+        })
+        .build();
+
+    let jobs = workload.jobs.max(1);
+    let semaphore = Arc::new(Semaphore::new(jobs));
+    let logical_requests = Arc::new(AtomicU64::new(0));
+    let patches_processed = Arc::new(AtomicU64::new(0));
+    let bytes_downloaded = Arc::new(AtomicU64::new(0));
+    let latencies_ms = Arc::new(Mutex::new(Vec::new()));
+
+    let paginated = PagedPatches {
+        client: client.clone(),
+    };
+    let started = Instant::now();
+
+    for platform in &workload.platforms {
+        let (platform_id, _) = platform.api_id_and_extension();
+        for page in workload.start_page..=workload.end_page {
+            logical_requests.fetch_add(1, Ordering::Relaxed);
+            let request = GetPatchesRequest {
+                platform: platform_id,
+                page,
+            };
+            let page_result = paginated.get_patches_page(request).await?;
+
+            let mut tasks = JoinSet::new();
+            for patch in page_result.patches {
+                let permit = Arc::clone(&semaphore).acquire_owned().await?;
+                let client = client.clone();
+                let logical_requests = Arc::clone(&logical_requests);
+                let patches_processed = Arc::clone(&patches_processed);
+                let bytes_downloaded = Arc::clone(&bytes_downloaded);
+                let latencies_ms = Arc::clone(&latencies_ms);
+                tasks.spawn(async move {
+                    let result = bench_one_patch(
+                        client,
+                        patch,
+                        logical_requests,
+                        patches_processed,
+                        bytes_downloaded,
+                        latencies_ms,
+                    )
+                    .await;
+                    drop(permit);
+                    result
+                });
+            }
+            while let Some(result) = tasks.join_next().await {
+                result??;
+            }
+        }
+    }
+
+    let mut latencies_ms = Arc::try_unwrap(latencies_ms)
+        .map(Mutex::into_inner)
+        .unwrap_or_default();
+    latencies_ms.sort_by(|a: &f64, b| a.total_cmp(b));
+
+    let report = Report {
+        patches_processed: patches_processed.load(Ordering::Relaxed),
+        bytes_downloaded: bytes_downloaded.load(Ordering::Relaxed),
+        wall_clock_ms: started.elapsed().as_millis(),
+        retries_triggered: attempts
+            .load(Ordering::Relaxed)
+            .saturating_sub(logical_requests.load(Ordering::Relaxed)),
+        latency: LatencyPercentiles {
+            p50_ms: percentile(&latencies_ms, 0.50),
+            p90_ms: percentile(&latencies_ms, 0.90),
+            p99_ms: percentile(&latencies_ms, 0.99),
+        },
+    };
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    if let Some(results_url) = &workload.results_url {
+        client.post(results_url).json(&report).send().await?;
+    }
+
+    Ok(())
+}
+
+async fn bench_one_patch(
+    client: ClientWithMiddleware,
+    patch: Patch,
+    logical_requests: Arc<AtomicU64>,
+    patches_processed: Arc<AtomicU64>,
+    bytes_downloaded: Arc<AtomicU64>,
+    latencies_ms: Arc<Mutex<Vec<f64>>>,
+) -> Result<()> {
+    let id = patch.id.as_u64().context("expected unsigned patch id")?;
+    let started = Instant::now();
+
+    logical_requests.fetch_add(1, Ordering::Relaxed);
+    let metadata = get_patch_metadata(&client, id).await?;
+    let patch_file = &metadata.files[0];
+
+    logical_requests.fetch_add(1, Ordering::Relaxed);
+    let buf = download_patch_bytes(&client, &patch_file.url).await?;
+
+    latencies_ms
+        .lock()
+        .await
+        .push(started.elapsed().as_secs_f64() * 1000.0);
+    bytes_downloaded.fetch_add(buf.len() as u64, Ordering::Relaxed);
+    patches_processed.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[rank]
+}
@@ -0,0 +1,105 @@
+use anyhow::{ensure, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Bump this whenever the on-disk manifest layout changes. A mismatched
+/// version invalidates the whole cache rather than attempting to migrate it.
+const CACHE_VERSION: u32 = 3;
+
+const CACHE_FILE_NAME: &str = ".patchstorage-cache";
+
+/// What we remember about a previously-downloaded patch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub slug: String,
+    pub file_id: u64,
+    pub filesize: u64,
+    /// Hex-encoded SHA-256 of the stored (post-transform) bytes. `file_id`
+    /// and `filesize` are what gate skipping a re-download; this is kept so
+    /// [`Manifest::changed_despite_matching_metadata`] can flag the rare
+    /// case where upstream silently swaps a file's content without bumping
+    /// either.
+    pub hash: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheData {
+    entries: HashMap<u64, CacheEntry>,
+}
+
+/// A persistent, compressed manifest of previously-downloaded patches, used
+/// to skip the byte download and store write for patches whose upstream
+/// file hasn't changed since the last run. Detecting that still requires a
+/// fresh metadata fetch per patch; only the download itself is cacheable.
+#[derive(Debug)]
+pub struct Manifest {
+    path: Utf8PathBuf,
+    data: CacheData,
+}
+
+impl Manifest {
+    /// Loads the manifest from `output_dir`, starting empty if it's missing,
+    /// unreadable, or was written by an incompatible cache version.
+    pub fn load(output_dir: &Utf8Path) -> Self {
+        let path = output_dir.join(CACHE_FILE_NAME);
+        let data = Self::read(&path).unwrap_or_default();
+        Manifest { path, data }
+    }
+
+    fn read(path: &Utf8Path) -> Result<CacheData> {
+        let raw = std::fs::read(path)?;
+        ensure!(raw.len() > 4, "cache file is too short");
+        let version = u32::from_le_bytes(raw[..4].try_into()?);
+        ensure!(version == CACHE_VERSION, "cache version {version} is stale");
+        let decompressed = zstd::stream::decode_all(&raw[4..])?;
+        let data = bincode::deserialize(&decompressed)?;
+        Ok(data)
+    }
+
+    /// True if `patch_id`'s upstream file is still the one we last fetched.
+    pub fn is_unchanged(&self, patch_id: u64, file_id: u64, filesize: u64) -> bool {
+        self.data
+            .entries
+            .get(&patch_id)
+            .is_some_and(|entry| entry.file_id == file_id && entry.filesize == filesize)
+    }
+
+    /// True if `patch_id`'s `file_id`/`filesize` match the last run but
+    /// `hash` doesn't — a sign upstream edited the file's bytes in place
+    /// without changing the id or size we'd otherwise trust to detect that.
+    pub fn changed_despite_matching_metadata(
+        &self,
+        patch_id: u64,
+        file_id: u64,
+        filesize: u64,
+        hash: &str,
+    ) -> bool {
+        self.data.entries.get(&patch_id).is_some_and(|entry| {
+            entry.file_id == file_id && entry.filesize == filesize && entry.hash != hash
+        })
+    }
+
+    pub fn update(&mut self, patch_id: u64, entry: CacheEntry) {
+        self.data.entries.insert(patch_id, entry);
+    }
+
+    /// Serializes, zstd-compresses, and writes the manifest back to disk.
+    pub fn save(&self) -> Result<()> {
+        let serialized = bincode::serialize(&self.data)?;
+        let compressed = zstd::stream::encode_all(&serialized[..], 0)?;
+        let mut file = std::fs::File::create(&self.path)?;
+        file.write_all(&CACHE_VERSION.to_le_bytes())?;
+        file.write_all(&compressed)?;
+        Ok(())
+    }
+}
+
+/// Hex-encoded SHA-256 of `buf`, used as the manifest's content hash.
+pub fn hash_bytes(buf: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(buf);
+    format!("{:x}", hasher.finalize())
+}
@@ -11,13 +11,23 @@ use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
 use serde::{Deserialize, Serialize};
 use serde_json::Number;
 use std::ffi::OsStr;
-use std::fs::File;
-use std::io::Write;
 use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::{Mutex, Semaphore};
+use tokio::task::JoinSet;
 
-#[derive(clap::ValueEnum, Clone, Debug, Default, PartialEq, Serialize)]
+mod bench;
+mod cache;
+mod store;
+mod transform;
+
+use cache::{CacheEntry, Manifest};
+use store::{FilesystemStore, Store};
+use transform::{Identity, SysExTrim, Transform};
+
+#[derive(clap::ValueEnum, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
-enum Platform {
+pub(crate) enum Platform {
     /// Meris LVX
     #[default]
     MerisLvx,
@@ -25,16 +35,34 @@ enum Platform {
     Zoia,
 }
 
+impl Platform {
+    /// The byte transform applied to a downloaded file before it's stored.
+    fn transform(&self, strict: bool) -> Box<dyn Transform> {
+        match self {
+            Platform::MerisLvx => Box::new(SysExTrim { strict }),
+            Platform::Zoia => Box::new(Identity),
+        }
+    }
+
+    /// The patchstorage.com platform id and the file extension it publishes.
+    pub(crate) fn api_id_and_extension(&self) -> (usize, &'static str) {
+        match self {
+            Platform::MerisLvx => (8008, "syx"),
+            Platform::Zoia => (3003, "bin"),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
-struct Patch {
-    id: Number,
-    slug: String,
+pub(crate) struct Patch {
+    pub(crate) id: Number,
+    pub(crate) slug: String,
 }
 
 #[derive(Clone)]
-struct GetPatchesRequest {
-    platform: usize,
-    page: usize,
+pub(crate) struct GetPatchesRequest {
+    pub(crate) platform: usize,
+    pub(crate) page: usize,
 }
 
 impl GetPatchesRequest {
@@ -46,17 +74,17 @@ impl GetPatchesRequest {
     }
 }
 
-struct PatchesPage {
-    patches: Vec<Patch>,
-    has_next: bool,
+pub(crate) struct PatchesPage {
+    pub(crate) patches: Vec<Patch>,
+    pub(crate) has_next: bool,
 }
 
-struct PagedPatches {
-    client: ClientWithMiddleware,
+pub(crate) struct PagedPatches {
+    pub(crate) client: ClientWithMiddleware,
 }
 
 impl PagedPatches {
-    async fn get_patches_page(&self, request: GetPatchesRequest) -> Result<PatchesPage> {
+    pub(crate) async fn get_patches_page(&self, request: GetPatchesRequest) -> Result<PatchesPage> {
         let response = self.client.get(&request.build()).send().await?;
         let has_next = self.has_next(&response.headers())?;
         let patches = response.json::<Vec<Patch>>().await?;
@@ -93,66 +121,57 @@ impl PageTurner<GetPatchesRequest> for PagedPatches {
 
 #[derive(Debug, Deserialize)]
 #[allow(dead_code)]
-struct PatchMetaData {
+pub(crate) struct PatchMetaData {
     id: Number,
     url: String,
     slug: String,
     title: String,
     content: String,
-    files: Vec<PatchFile>,
+    pub(crate) files: Vec<PatchFile>,
 }
 
 #[derive(Debug, Deserialize)]
 #[allow(dead_code)]
-struct PatchFile {
-    id: Number,
-    url: String,
-    filesize: Number,
+pub(crate) struct PatchFile {
+    pub(crate) id: Number,
+    pub(crate) url: String,
+    pub(crate) filesize: Number,
     filename: String,
 }
 
-async fn get_patch_metadata(client: &ClientWithMiddleware, id: u64) -> Result<PatchMetaData> {
+/// The retry policy shared by the normal download path and `--bench`, so a
+/// benchmark run measures the same retry behavior real downloads get.
+pub(crate) fn retry_middleware() -> RetryTransientMiddleware<ExponentialBackoff> {
+    let retry_policy = ExponentialBackoff::builder().build_with_max_retries(5);
+    RetryTransientMiddleware::new_with_policy(retry_policy)
+}
+
+pub(crate) async fn get_patch_metadata(
+    client: &ClientWithMiddleware,
+    id: u64,
+) -> Result<PatchMetaData> {
     let url = format!("https://patchstorage.com/api/beta/patches/{id}");
     let response = client.get(&url).send().await?;
     let metadata = response.json::<PatchMetaData>().await?;
     Ok(metadata)
 }
 
-async fn get_patch_bytes(client: &ClientWithMiddleware, url: &str) -> Result<Vec<u8>> {
+/// Streams the patch file into memory chunk by chunk. `Store::put` itself
+/// takes a stream and never forces a full buffer, but the platform's
+/// `Transform` step needs the complete bytes to scan (e.g. to find a SysEx
+/// frame), so this pipeline still buffers the whole file before it's handed
+/// off for storage.
+pub(crate) async fn download_patch_bytes(
+    client: &ClientWithMiddleware,
+    url: &str,
+) -> Result<Vec<u8>> {
     let response = client.get(url).send().await?;
-    let bytes = response.bytes().await?;
-    Ok(bytes.to_vec())
-}
-
-fn sysex_filter(buf: &[u8]) -> Option<&[u8]> {
-    let len = buf.len();
-    let mut first = len;
-    for i in 0..len {
-        if buf[i] >= 0xF0 {
-            first = i;
-            break;
-        }
-    }
-    if first == len || buf[first] != 0xF0 {
-        // F0 not found
-        // TODO: or found another system message
-        return None;
-    }
-    let mut last = len;
-    for j in (first + 1)..len {
-        if buf[j] == 0xF7 {
-            last = j;
-            break;
-        }
-    }
-    if last == len {
-        // F7 not found
-        return None;
+    let mut stream = response.bytes_stream();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = stream.try_next().await? {
+        bytes.extend_from_slice(&chunk);
     }
-    if first > 0 || last < len - 1 {
-        return Some(&buf[first..=last]);
-    }
-    None // Nothing trimmed
+    Ok(bytes)
 }
 
 #[derive(Debug, Parser)]
@@ -169,80 +188,152 @@ struct Args {
     /// Platform
     #[clap(short, long, default_value_t, value_enum)]
     platform: Platform,
+
+    /// Maximum number of patches to download concurrently
+    #[clap(short, long, default_value = "4")]
+    jobs: usize,
+
+    /// Error out if a downloaded Meris LVX file contains no valid SysEx
+    /// message, instead of writing it unfiltered
+    #[clap(long, default_value = "false")]
+    strict: bool,
+
+    /// Run a benchmark workload from this JSON file instead of downloading
+    /// patches, and report throughput stats
+    #[clap(long)]
+    bench: Option<Utf8PathBuf>,
+}
+
+/// Downloads a single patch: fetches its metadata, downloads the matching
+/// file, runs it through the platform's byte filter, and puts it in the
+/// store.
+async fn process_patch(
+    client: ClientWithMiddleware,
+    args: Arc<Args>,
+    store: Arc<dyn Store>,
+    manifest: Arc<Mutex<Manifest>>,
+    extension: &'static str,
+    patch: Patch,
+) -> Result<()> {
+    println!("{patch:#?}");
+
+    let key = format!("{}.{extension}", patch.slug);
+
+    let id = patch.id.as_u64().context("expected unsigned patch id")?;
+    let metadata = get_patch_metadata(&client, id).await?;
+    println!("{metadata:#?}");
+
+    let patch_file = &metadata.files[0];
+    let patch_file_extension = Path::new(&patch_file.filename)
+        .extension()
+        .and_then(OsStr::to_str);
+    if patch_file_extension != Some(extension) {
+        println!("Skipping file: {}", patch_file.filename);
+        return Ok(());
+    }
+
+    let file_id = patch_file
+        .id
+        .as_u64()
+        .context("expected unsigned file id")?;
+    let filesize = patch_file
+        .filesize
+        .as_u64()
+        .context("expected unsigned filesize")?;
+
+    // Metadata is always fetched fresh above; only the byte download and
+    // store write are skippable, since we need the current file id/size to
+    // know whether the manifest entry is still up to date.
+    if store.exists(&key).await? {
+        if args.overwrite {
+            println!("Overwriting file: {key}");
+        } else if manifest.lock().await.is_unchanged(id, file_id, filesize) {
+            println!("Retaining file: {key}");
+            return Ok(());
+        }
+    }
+
+    let mut buf = download_patch_bytes(&client, &patch_file.url).await?;
+    println!("Read {} bytes", buf.len());
+
+    buf = args.platform.transform(args.strict).apply(buf)?;
+    let hash = cache::hash_bytes(&buf);
+
+    if manifest
+        .lock()
+        .await
+        .changed_despite_matching_metadata(id, file_id, filesize, &hash)
+    {
+        println!("Warning: content for patch {id} changed despite matching file id/size");
+    }
+
+    store.put(&key, store::once(buf)).await?;
+    println!("Wrote file: {key}");
+
+    let entry = CacheEntry {
+        slug: patch.slug,
+        file_id,
+        filesize,
+        hash,
+    };
+    manifest.lock().await.update(id, entry);
+
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
     dbg!(&args);
+
+    if let Some(workload_path) = &args.bench {
+        return bench::run(workload_path).await;
+    }
+
     ensure!(
         args.output_dir.exists(),
         "output directory `{}` doesn't exist",
         args.output_dir
     );
 
-    let (platform, extension) = match args.platform {
-        Platform::MerisLvx => (8008, "syx"),
-        Platform::Zoia => (3003, "bin"),
-    };
+    let (platform, extension) = args.platform.api_id_and_extension();
 
     // reqwest client that retries failed requests
-    let retry_policy = ExponentialBackoff::builder().build_with_max_retries(5);
     let client = ClientBuilder::new(Client::new())
-        .with(RetryTransientMiddleware::new_with_policy(retry_policy))
+        .with(retry_middleware())
         .build();
 
+    let jobs = args.jobs.max(1);
+    let semaphore = Arc::new(Semaphore::new(jobs));
+    let manifest = Arc::new(Mutex::new(Manifest::load(&args.output_dir)));
+    let store: Arc<dyn Store> = Arc::new(FilesystemStore::new(args.output_dir.clone()));
+    let args = Arc::new(args);
+
     let paginated = PagedPatches {
         client: client.clone(),
     };
     let mut pager = std::pin::pin!(paginated.pages(GetPatchesRequest { platform, page: 1 }));
     while let Some(patches) = pager.try_next().await? {
         println!("Processing {} patches", patches.len());
+
+        let mut tasks = JoinSet::new();
         for patch in patches {
-            println!("{patch:#?}");
-
-            let mut filename = args.output_dir.join(&patch.slug);
-            filename.set_extension(extension);
-
-            if filename.exists() {
-                if args.overwrite {
-                    println!("Overwriting file: {filename}");
-                } else {
-                    println!("Retaining file: {filename}");
-                    continue;
-                }
-            }
-
-            let id = patch.id.as_u64().context("expected unsigned patch id")?;
-            let metadata = get_patch_metadata(&client, id).await?;
-            println!("{metadata:#?}");
-
-            let patch_file = &metadata.files[0];
-            let patch_file_extension = Path::new(&patch_file.filename)
-                .extension()
-                .and_then(OsStr::to_str);
-            if patch_file_extension != Some(&extension) {
-                println!("Skipping file: {}", patch_file.filename);
-                continue;
-            }
-
-            let mut buf = get_patch_bytes(&client, &patch_file.url).await?;
-            println!("Read {} bytes", buf.len());
-
-            // TODO: Strategy
-            if args.platform == Platform::MerisLvx {
-                if let Some(filtered) = sysex_filter(&buf) {
-                    buf = filtered.to_vec();
-                    println!("Writing {} bytes", buf.len());
-                } else {
-                    println!("Nothing filtered.");
-                }
-            }
-
-            let mut file = File::create(&filename)?;
-            println!("Writing file: {filename}");
-            file.write_all(&buf)?;
+            let permit = Arc::clone(&semaphore).acquire_owned().await?;
+            let client = client.clone();
+            let args = Arc::clone(&args);
+            let store = Arc::clone(&store);
+            let manifest = Arc::clone(&manifest);
+            tasks.spawn(async move {
+                let result = process_patch(client, args, store, manifest, extension, patch).await;
+                drop(permit);
+                result
+            });
+        }
+        while let Some(result) = tasks.join_next().await {
+            result??;
         }
+
+        manifest.lock().await.save()?;
     }
     Ok(())
 }
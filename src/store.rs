@@ -0,0 +1,68 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use camino::Utf8PathBuf;
+use futures_util::{Stream, StreamExt};
+use std::pin::Pin;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+/// A stream of byte chunks handed to [`Store::put`]. Taking a stream instead
+/// of a single buffer lets a backend write each chunk through as it arrives
+/// rather than requiring the whole file in memory up front.
+pub type ByteStream<'a> = Pin<Box<dyn Stream<Item = Result<Bytes>> + Send + 'a>>;
+
+/// Wraps an already-buffered `Vec<u8>` as a single-chunk [`ByteStream`], for
+/// callers that only have the whole file in hand (e.g. after running it
+/// through a [`crate::transform::Transform`], which needs the complete
+/// bytes to scan).
+pub fn once(bytes: Vec<u8>) -> ByteStream<'static> {
+    Box::pin(futures_util::stream::once(
+        async move { Ok(Bytes::from(bytes)) },
+    ))
+}
+
+/// Where downloaded patch bytes end up. Keeps the download pipeline from
+/// touching the filesystem directly, so a backend like object storage can
+/// be swapped in later by changing only the store `main` constructs.
+#[async_trait]
+pub trait Store: Send + Sync {
+    /// Whether `key` is already present, so callers can preserve the
+    /// existing overwrite/skip logic.
+    async fn exists(&self, key: &str) -> Result<bool>;
+
+    /// Writes `stream` to `key`, replacing any existing contents.
+    async fn put(&self, key: &str, stream: ByteStream<'_>) -> Result<()>;
+}
+
+/// Stores patches as files under a local directory, the tool's original
+/// (and still default) behavior.
+pub struct FilesystemStore {
+    root: Utf8PathBuf,
+}
+
+impl FilesystemStore {
+    pub fn new(root: Utf8PathBuf) -> Self {
+        FilesystemStore { root }
+    }
+}
+
+#[async_trait]
+impl Store for FilesystemStore {
+    async fn exists(&self, key: &str) -> Result<bool> {
+        Ok(self.root.join(key).exists())
+    }
+
+    async fn put(&self, key: &str, mut stream: ByteStream<'_>) -> Result<()> {
+        let dest = self.root.join(key);
+        let tmp_path = Utf8PathBuf::from(format!("{dest}.tmp"));
+        let mut tmp_file = fs::File::create(&tmp_path).await?;
+        while let Some(chunk) = stream.next().await {
+            tmp_file.write_all(&chunk?).await?;
+        }
+        tmp_file.flush().await?;
+        drop(tmp_file);
+        fs::rename(&tmp_path, &dest).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use anyhow::{bail, Result};
+
+/// A per-platform byte transform applied to a downloaded patch before it's
+/// handed to the `Store`. New platforms plug in their own quirks (header
+/// stripping, checksum fixups, ...) by adding a `Transform` impl instead of
+/// branching in the download pipeline.
+pub trait Transform: Send + Sync {
+    fn apply(&self, buf: Vec<u8>) -> Result<Vec<u8>>;
+}
+
+/// Leaves bytes untouched, for platforms whose files need no massaging.
+pub struct Identity;
+
+impl Transform for Identity {
+    fn apply(&self, buf: Vec<u8>) -> Result<Vec<u8>> {
+        Ok(buf)
+    }
+}
+
+/// Trims everything outside the bundled SysEx message(s), as Meris LVX dumps
+/// wrap the patch in extra bytes the device doesn't expect on reimport.
+/// A download with several concatenated messages is reassembled in order.
+pub struct SysExTrim {
+    /// When set, a download with no valid SysEx message is an error rather
+    /// than a passthrough — a strong signal the upstream file is corrupt.
+    pub strict: bool,
+}
+
+impl Transform for SysExTrim {
+    fn apply(&self, buf: Vec<u8>) -> Result<Vec<u8>> {
+        let SysExResult { bytes, outcome } = sysex_filter(&buf);
+        match outcome {
+            SysExOutcome::NoSysEx if self.strict => {
+                bail!("no valid SysEx message found in Meris LVX download")
+            }
+            SysExOutcome::NoSysEx => println!("No SysEx message found, writing as-is."),
+            SysExOutcome::Trimmed { junk_bytes: 0 } => println!("SysEx message already clean."),
+            SysExOutcome::Trimmed { junk_bytes } => {
+                println!("Trimmed {junk_bytes} bytes of junk around the SysEx message.")
+            }
+            SysExOutcome::Reassembled { messages } => {
+                println!("Reassembled {messages} SysEx messages.")
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+/// What `sysex_filter` found in a buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SysExOutcome {
+    /// No well-formed `F0 ... F7` frame was found.
+    NoSysEx,
+    /// A single frame was found; `junk_bytes` were outside it (0 if none).
+    Trimmed { junk_bytes: usize },
+    /// Multiple frames were found and concatenated in order.
+    Reassembled { messages: usize },
+}
+
+struct SysExResult {
+    bytes: Vec<u8>,
+    outcome: SysExOutcome,
+}
+
+/// Scans `buf` for every well-formed `F0 ... F7` SysEx frame and
+/// concatenates them in order. A byte with the high bit set inside a frame
+/// is illegal in SysEx data, so such a frame is rejected rather than
+/// emitted truncated or corrupt.
+fn sysex_filter(buf: &[u8]) -> SysExResult {
+    let mut frames = Vec::new();
+    let mut i = 0;
+    while i < buf.len() {
+        if buf[i] != 0xF0 {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        let mut end = None;
+        let mut j = i + 1;
+        while j < buf.len() {
+            match buf[j] {
+                0xF7 => {
+                    end = Some(j);
+                    break;
+                }
+                byte if byte & 0x80 != 0 => break, // illegal status byte inside the frame
+                _ => j += 1,
+            }
+        }
+        match end {
+            Some(last) => {
+                frames.push(&buf[start..=last]);
+                i = last + 1;
+            }
+            None => i += 1, // malformed frame; keep scanning past the stray F0
+        }
+    }
+
+    match frames.len() {
+        0 => SysExResult {
+            bytes: buf.to_vec(),
+            outcome: SysExOutcome::NoSysEx,
+        },
+        1 => {
+            let frame = frames[0];
+            let junk_bytes = buf.len() - frame.len();
+            SysExResult {
+                bytes: frame.to_vec(),
+                outcome: SysExOutcome::Trimmed { junk_bytes },
+            }
+        }
+        messages => {
+            let bytes = frames.concat();
+            SysExResult {
+                bytes,
+                outcome: SysExOutcome::Reassembled { messages },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_input_has_no_sysex() {
+        let result = sysex_filter(&[]);
+        assert_eq!(result.outcome, SysExOutcome::NoSysEx);
+        assert_eq!(result.bytes, Vec::<u8>::new());
+    }
+
+    #[test]
+    fn single_clean_message_is_untrimmed() {
+        let msg = [0xF0, 0x01, 0x02, 0xF7];
+        let result = sysex_filter(&msg);
+        assert_eq!(result.outcome, SysExOutcome::Trimmed { junk_bytes: 0 });
+        assert_eq!(result.bytes, msg);
+    }
+
+    #[test]
+    fn leading_and_trailing_garbage_is_trimmed() {
+        let buf = [0x00, 0x00, 0xF0, 0x01, 0x02, 0xF7, 0xFF];
+        let result = sysex_filter(&buf);
+        assert_eq!(result.outcome, SysExOutcome::Trimmed { junk_bytes: 3 });
+        assert_eq!(result.bytes, [0xF0, 0x01, 0x02, 0xF7]);
+    }
+
+    #[test]
+    fn back_to_back_messages_are_reassembled() {
+        let buf = [0xF0, 0x01, 0xF7, 0xF0, 0x02, 0xF7];
+        let result = sysex_filter(&buf);
+        assert_eq!(result.outcome, SysExOutcome::Reassembled { messages: 2 });
+        assert_eq!(result.bytes, [0xF0, 0x01, 0xF7, 0xF0, 0x02, 0xF7]);
+    }
+}